@@ -1,12 +1,60 @@
-use std::ops::Deref;
+use std::cmp::Ordering;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Hashes `value` into a single `u64` fingerprint, used to detect whether a
+/// value actually changed across a `write_guard()` edit.
+fn hash_value<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
 
 /// Dirty wraps a value of type T with functions similiar to that of a Read/Write
 /// lock but simply sets a dirty flag on write(), reset on clear().
 /// Use read() or deref (*dirty_variable) to access the inner value.
-#[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Default, Hash)]
+#[derive(Copy, Clone, Debug, Default)]
 pub struct Dirty<T> {
     value: T,
     dirty: bool,
+    checkpoint: Option<u64>,
+    poisoned: bool,
+}
+
+// PartialEq/Eq/PartialOrd/Ord/Hash are hand-rolled rather than derived so
+// that `checkpoint`/`poisoned` bookkeeping doesn't leak into comparisons and
+// hashing of the logical value.
+impl<T: PartialEq> PartialEq for Dirty<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.dirty == other.dirty
+    }
+}
+
+impl<T: Eq> Eq for Dirty<T> {}
+
+impl<T: PartialOrd> PartialOrd for Dirty<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.value.partial_cmp(&other.value) {
+            Some(Ordering::Equal) => self.dirty.partial_cmp(&other.dirty),
+            ord => ord,
+        }
+    }
+}
+
+impl<T: Ord> Ord for Dirty<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.value.cmp(&other.value).then(self.dirty.cmp(&other.dirty))
+    }
+}
+
+impl<T: Hash> Hash for Dirty<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state);
+        self.dirty.hash(state);
+    }
 }
 
 impl<T> Dirty<T> {
@@ -15,6 +63,8 @@ impl<T> Dirty<T> {
         Dirty {
             value: val,
             dirty: true,
+            checkpoint: None,
+            poisoned: false,
         }
     }
 
@@ -23,6 +73,8 @@ impl<T> Dirty<T> {
         Dirty {
             value: val,
             dirty: false,
+            checkpoint: None,
+            poisoned: false,
         }
     }
 
@@ -55,17 +107,99 @@ impl<T> Dirty<T> {
         }
     }
 
-    /// Write new value only if dirty, returning whether the value was written or not
-    pub fn write_dirty<F>(&mut self, f: F) -> bool
-    where F: Fn(&T) -> T {
-        if self.dirty { self.value = f(&self.value); }
-        self.dirty
+    /// Write new value only if dirty. Poisons (see `is_poisoned`) and propagates if `f` panics.
+    pub fn write_dirty<F>(&mut self, f: F) -> Result<bool, DirtyPoisonError<'_, T>>
+    where
+        F: Fn(&T) -> T,
+    {
+        match self.recompute(f) {
+            Ok(dirty) => Ok(dirty),
+            Err(Some(payload)) => panic::resume_unwind(payload),
+            Err(None) => Err(DirtyPoisonError { value: &self.value }),
+        }
+    }
+
+    /// Like `write_dirty`, but if `f` panics, poisons and returns a `DirtyPoisonError` instead of
+    /// unwinding. The panic payload itself is dropped.
+    pub fn try_write_dirty<F>(&mut self, f: F) -> Result<bool, DirtyPoisonError<'_, T>>
+    where
+        F: Fn(&T) -> T,
+    {
+        match self.recompute(f) {
+            Ok(dirty) => Ok(dirty),
+            Err(_) => Err(DirtyPoisonError { value: &self.value }),
+        }
+    }
+
+    /// Shared recompute path: `Err(Some(payload))` is a fresh panic, `Err(None)` already poisoned.
+    fn recompute<F>(&mut self, f: F) -> Result<bool, Option<Box<dyn std::any::Any + Send>>>
+    where
+        F: Fn(&T) -> T,
+    {
+        if self.poisoned {
+            return Err(None);
+        }
+        if self.dirty {
+            let value = &self.value;
+            match panic::catch_unwind(AssertUnwindSafe(|| f(value))) {
+                Ok(new_value) => self.value = new_value,
+                Err(payload) => {
+                    self.poisoned = true;
+                    return Err(Some(payload));
+                }
+            }
+        }
+        Ok(self.dirty)
+    }
+
+    /// Returns true if a previous recompute panicked, leaving the wrapper poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Clears the poisoned flag, declaring the current value safe to use.
+    pub fn clear_poison(&mut self) {
+        self.poisoned = false;
     }
 
     /// Consumes the wrapper and returns the enclosed value
     pub fn unwrap(self) -> T {
         self.value
     }
+
+    /// RAII write access; only marks dirty on `Drop` if the value's hash changed.
+    pub fn write_guard(&mut self) -> DirtyGuard<'_, T>
+    where
+        T: Hash,
+    {
+        let hash = hash_value(&self.value);
+        DirtyGuard { dirty: self, hash }
+    }
+
+    /// Stores a fingerprint of the current value, to be compared against by
+    /// a later call to `changed()`. Independent of the `dirty()` flag: this
+    /// tracks whether the value's content actually differs, not whether it
+    /// was touched.
+    pub fn checkpoint(&mut self)
+    where
+        T: Hash,
+    {
+        self.checkpoint = Some(hash_value(&self.value));
+    }
+
+    /// Returns true if the value's content differs from the last
+    /// `checkpoint()`, or if no checkpoint has been taken yet. Unlike
+    /// `dirty()`, this detects whether the value effectively changed rather
+    /// than whether it was written to.
+    pub fn changed(&self) -> bool
+    where
+        T: Hash,
+    {
+        match self.checkpoint {
+            Some(hash) => hash_value(&self.value) != hash,
+            None => true,
+        }
+    }
 }
 
 impl<T> Deref for Dirty<T> {
@@ -75,9 +209,187 @@ impl<T> Deref for Dirty<T> {
     }
 }
 
+/// Error returned by `write_dirty`/`try_write_dirty` when the `Dirty` is poisoned.
+#[derive(Debug)]
+pub struct DirtyPoisonError<'a, T> {
+    value: &'a T,
+}
+
+impl<'a, T> DirtyPoisonError<'a, T> {
+    /// Consumes the error, returning a reference to the poisoned value.
+    pub fn into_inner(self) -> &'a T {
+        self.value
+    }
+
+    /// Returns a reference to the poisoned value.
+    pub fn get_ref(&self) -> &T {
+        self.value
+    }
+}
+
+/// RAII guard returned by [`Dirty::write_guard`].
+pub struct DirtyGuard<'a, T: Hash> {
+    dirty: &'a mut Dirty<T>,
+    hash: u64,
+}
+
+impl<'a, T: Hash> Deref for DirtyGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.dirty.value
+    }
+}
+
+impl<'a, T: Hash> DerefMut for DirtyGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.dirty.value
+    }
+}
+
+impl<'a, T: Hash> Drop for DirtyGuard<'a, T> {
+    fn drop(&mut self) {
+        if hash_value(&self.dirty.value) != self.hash {
+            self.dirty.dirty = true;
+        }
+    }
+}
+
+/// Caches a value `U` derived from a `Dirty<T>` source, recomputed lazily when dirty.
+pub struct Derived<T, U, F>
+where
+    F: Fn(&T) -> U,
+{
+    source: Dirty<T>,
+    compute: F,
+    cache: Option<U>,
+}
+
+impl<T, U, F> Derived<T, U, F>
+where
+    F: Fn(&T) -> U,
+{
+    /// Create a new Derived wrapping `val`, using `compute` to produce `U`.
+    pub fn new(val: T, compute: F) -> Derived<T, U, F> {
+        Derived {
+            source: Dirty::new(val),
+            compute,
+            cache: None,
+        }
+    }
+
+    /// Returns the derived value, recomputing it only if the source is dirty.
+    pub fn get(&mut self) -> &U {
+        if self.source.dirty() || self.cache.is_none() {
+            self.cache = Some((self.compute)(self.source.read()));
+            self.source.clear();
+        }
+        self.cache.as_ref().unwrap()
+    }
+
+    /// Writable access to the source value; invalidates the cached derived value.
+    pub fn set(&mut self) -> &mut T {
+        self.cache = None;
+        self.source.write()
+    }
+
+    /// Reads the cached derived value without forcing a recompute.
+    pub fn peek(&self) -> Option<&U> {
+        self.cache.as_ref()
+    }
+}
+
+/// Thread-safe companion to `Dirty`, built on an `RwLock<Dirty<T>>`.
+pub struct SharedDirty<T> {
+    inner: RwLock<Dirty<T>>,
+}
+
+impl<T> SharedDirty<T> {
+    /// Create a new SharedDirty.
+    pub fn new(val: T) -> SharedDirty<T> {
+        SharedDirty {
+            inner: RwLock::new(Dirty::new(val)),
+        }
+    }
+
+    /// Create a new SharedDirty with a clear dirty flag.
+    pub fn new_clean(val: T) -> SharedDirty<T> {
+        SharedDirty {
+            inner: RwLock::new(Dirty::new_clean(val)),
+        }
+    }
+
+    /// Returns true if dirty, false otherwise.
+    pub fn dirty(&self) -> bool {
+        self.inner.read().unwrap_or_else(|e| e.into_inner()).dirty()
+    }
+
+    /// Shared read access to the value. Never touches the dirty flag.
+    pub fn read(&self) -> SharedDirtyReadGuard<'_, T> {
+        SharedDirtyReadGuard(ReadGuardInner::Shared(
+            self.inner.read().unwrap_or_else(|e| e.into_inner()),
+        ))
+    }
+
+    /// Exclusive write access to the value. Marks dirty when the guard is dropped.
+    pub fn write(&self) -> SharedDirtyWriteGuard<'_, T> {
+        SharedDirtyWriteGuard(self.inner.write().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Atomically returns a read guard and clears the dirty flag, but only if it was set.
+    pub fn take_dirty(&self) -> Option<SharedDirtyReadGuard<'_, T>> {
+        let mut guard = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        if guard.dirty() {
+            guard.clear();
+            Some(SharedDirtyReadGuard(ReadGuardInner::Exclusive(guard)))
+        } else {
+            None
+        }
+    }
+}
+
+enum ReadGuardInner<'a, T> {
+    Shared(RwLockReadGuard<'a, Dirty<T>>),
+    Exclusive(RwLockWriteGuard<'a, Dirty<T>>),
+}
+
+/// RAII read guard returned by [`SharedDirty::read`] and [`SharedDirty::take_dirty`].
+pub struct SharedDirtyReadGuard<'a, T>(ReadGuardInner<'a, T>);
+
+impl<'a, T> Deref for SharedDirtyReadGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        match &self.0 {
+            ReadGuardInner::Shared(guard) => guard.read(),
+            ReadGuardInner::Exclusive(guard) => guard.read(),
+        }
+    }
+}
+
+/// RAII write guard returned by [`SharedDirty::write`]. Marks dirty on `Drop`.
+pub struct SharedDirtyWriteGuard<'a, T>(RwLockWriteGuard<'a, Dirty<T>>);
+
+impl<'a, T> Deref for SharedDirtyWriteGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.0.read()
+    }
+}
+
+impl<'a, T> DerefMut for SharedDirtyWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0.value
+    }
+}
+
+impl<'a, T> Drop for SharedDirtyWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.0.dirty = true;
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Dirty;
+    use super::{Derived, Dirty, SharedDirty};
 
     #[test]
     fn new_dirty() {
@@ -128,12 +440,74 @@ mod tests {
     #[test]
     fn write_dirty() {
         let mut dirty = Dirty::new_clean(0);
-        assert!(!dirty.write_dirty(|_| 3));
+        assert!(matches!(dirty.write_dirty(|_| 3), Ok(false)));
         *dirty.write() += 3;
-        assert!(dirty.write_dirty(|_| [1, 2, 3].iter().copied().reduce(|acc, x| acc + x).unwrap()));
+        assert!(matches!(
+            dirty.write_dirty(|_| [1, 2, 3].iter().copied().reduce(|acc, x| acc + x).unwrap()),
+            Ok(true)
+        ));
         assert_eq!(*dirty.read(), 6);
     }
 
+    #[test]
+    fn write_dirty_poisons_on_panic_and_propagates() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let mut dirty = Dirty::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = dirty.write_dirty(|_| panic!("boom"));
+        }));
+        assert!(result.is_err());
+        assert!(dirty.is_poisoned());
+    }
+
+    #[test]
+    fn write_dirty_errors_once_poisoned() {
+        let mut dirty = Dirty::new(5);
+        dirty.clear_poison();
+        // manually drive the wrapper into a poisoned state
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = dirty.write_dirty(|_: &i32| panic!("boom"));
+        }));
+        assert!(dirty.is_poisoned());
+
+        match dirty.write_dirty(|v| v + 1) {
+            Err(err) => assert_eq!(*err.get_ref(), 5),
+            Ok(_) => panic!("expected a poison error"),
+        }
+
+        dirty.clear_poison();
+        assert!(!dirty.is_poisoned());
+        assert!(matches!(dirty.write_dirty(|v| v + 1), Ok(true)));
+        assert_eq!(*dirty.read(), 6);
+    }
+
+    #[test]
+    fn try_write_dirty_catches_panic_without_unwinding() {
+        let mut dirty = Dirty::new(10);
+        let result = dirty.try_write_dirty(|_| panic!("boom"));
+        assert!(result.is_err());
+        assert!(dirty.is_poisoned());
+        assert_eq!(*dirty.read(), 10);
+    }
+
+    #[test]
+    fn try_write_dirty_recomputes_when_clean_run() {
+        let mut dirty = Dirty::new(10);
+        assert!(matches!(dirty.try_write_dirty(|v| v + 1), Ok(true)));
+        assert_eq!(*dirty.read(), 11);
+    }
+
+    #[test]
+    fn poisoned_does_not_affect_equality() {
+        let mut poisoned = Dirty::new(10);
+        let _ = poisoned.try_write_dirty(|_| panic!("boom"));
+        assert!(poisoned.is_poisoned());
+
+        let plain = Dirty::new(10);
+        assert_eq!(plain, poisoned);
+    }
+
     #[test]
     fn access_inner_deref() {
         let dirty = Dirty::new(0);
@@ -152,4 +526,194 @@ mod tests {
         *dirty.write() = 200;
         assert_eq!(dirty.unwrap(), 200);
     }
+
+    #[test]
+    fn write_guard_leaves_clean_if_unchanged() {
+        let mut dirty = Dirty::new_clean(0);
+        assert!(!dirty.dirty());
+        {
+            let guard = dirty.write_guard();
+            assert_eq!(*guard, 0);
+        }
+        assert!(!dirty.dirty());
+    }
+
+    #[test]
+    fn write_guard_sets_dirty_on_real_change() {
+        let mut dirty = Dirty::new_clean(0);
+        assert!(!dirty.dirty());
+        {
+            let mut guard = dirty.write_guard();
+            *guard += 1;
+        }
+        assert!(dirty.dirty());
+        assert_eq!(*dirty.read(), 1);
+    }
+
+    #[test]
+    fn write_guard_no_dirty_if_value_written_back_unchanged() {
+        let mut dirty = Dirty::new_clean(5);
+        {
+            let mut guard = dirty.write_guard();
+            *guard += 1;
+            *guard -= 1;
+        }
+        assert!(!dirty.dirty());
+    }
+
+    #[test]
+    fn changed_true_before_any_checkpoint() {
+        let dirty = Dirty::new_clean(0);
+        assert!(dirty.changed());
+    }
+
+    #[test]
+    fn changed_false_immediately_after_checkpoint() {
+        let mut dirty = Dirty::new(0);
+        dirty.checkpoint();
+        assert!(!dirty.changed());
+    }
+
+    #[test]
+    fn changed_detects_content_diff_independent_of_dirty_flag() {
+        let mut dirty = Dirty::new_clean(5);
+        dirty.checkpoint();
+        assert!(!dirty.changed());
+
+        // touched via write(), but ends up back at the checkpointed value
+        *dirty.write() += 1;
+        assert!(dirty.dirty());
+        assert!(dirty.changed());
+
+        *dirty.write() -= 1;
+        assert!(dirty.dirty());
+        assert!(!dirty.changed());
+    }
+
+    #[test]
+    fn checkpoint_does_not_affect_equality_or_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let plain = Dirty::new_clean(5);
+        let mut checkpointed = Dirty::new_clean(5);
+        checkpointed.checkpoint();
+
+        assert_eq!(plain, checkpointed);
+
+        let mut hasher_a = DefaultHasher::new();
+        let mut hasher_b = DefaultHasher::new();
+        plain.hash(&mut hasher_a);
+        checkpointed.hash(&mut hasher_b);
+        assert_eq!(hasher_a.finish(), hasher_b.finish());
+    }
+
+    #[test]
+    fn derived_recomputes_on_first_get() {
+        let mut derived = Derived::new(2, |v: &i32| v * 10);
+        assert_eq!(derived.peek(), None);
+        assert_eq!(*derived.get(), 20);
+    }
+
+    #[test]
+    fn derived_caches_until_source_changes() {
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let mut derived = Derived::new(2, |v: &i32| {
+            calls.set(calls.get() + 1);
+            v * 10
+        });
+        assert_eq!(*derived.get(), 20);
+        assert_eq!(*derived.get(), 20);
+        assert_eq!(calls.get(), 1);
+
+        *derived.set() = 3;
+        assert_eq!(*derived.get(), 30);
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn derived_peek_does_not_force_recompute() {
+        let mut derived = Derived::new(2, |v: &i32| v * 10);
+        assert_eq!(derived.peek(), None);
+        derived.get();
+        assert_eq!(derived.peek(), Some(&20));
+        *derived.set() = 5;
+        assert_eq!(derived.peek(), None);
+    }
+
+    #[test]
+    fn shared_dirty_read_doesnt_clear_flag() {
+        let shared = SharedDirty::new(1);
+        assert!(shared.dirty());
+        assert_eq!(*shared.read(), 1);
+        assert!(shared.dirty());
+    }
+
+    #[test]
+    fn shared_dirty_write_sets_flag_on_drop() {
+        let shared = SharedDirty::new_clean(1);
+        assert!(!shared.dirty());
+        {
+            let mut guard = shared.write();
+            *guard += 1;
+        }
+        assert!(shared.dirty());
+        assert_eq!(*shared.read(), 2);
+    }
+
+    #[test]
+    fn shared_dirty_take_dirty_drains_exactly_once() {
+        let shared = SharedDirty::new_clean(0);
+        assert!(shared.take_dirty().is_none());
+
+        *shared.write() += 1;
+        assert!(shared.dirty());
+
+        {
+            let drained = shared.take_dirty().unwrap();
+            assert_eq!(*drained, 1);
+        }
+        assert!(!shared.dirty());
+        assert!(shared.take_dirty().is_none());
+    }
+
+    #[test]
+    fn shared_dirty_across_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let shared = Arc::new(SharedDirty::new_clean(0));
+        let producer = Arc::clone(&shared);
+        thread::spawn(move || {
+            *producer.write() += 42;
+        })
+        .join()
+        .unwrap();
+
+        let drained = shared.take_dirty().unwrap();
+        assert_eq!(*drained, 42);
+    }
+
+    #[test]
+    fn shared_dirty_survives_panic_in_write_guard() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let shared = Arc::new(SharedDirty::new_clean(0));
+        let panicker = Arc::clone(&shared);
+        let result = thread::spawn(move || {
+            let mut guard = panicker.write();
+            *guard += 1;
+            panic!("boom");
+        })
+        .join();
+        assert!(result.is_err());
+
+        assert!(shared.dirty());
+        assert_eq!(*shared.read(), 1);
+        *shared.write() += 1;
+        assert_eq!(*shared.take_dirty().unwrap(), 2);
+    }
 }